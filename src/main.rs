@@ -1,6 +1,7 @@
 mod payload;
 
 use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 
 use clap::{ArgEnum, Parser};
@@ -27,13 +28,33 @@ pub struct Cli {
     #[clap(short, long, value_parser, default_value = "little")]
     endianness: Endianness,
 
-    /// Start address for .vhx files, only relevant when converting .vhx -> .hex
-    #[clap(short, long, value_parser = legal_u32, default_value_t = 0)]
-    start_address: u32,
+    /// Start address for .vhx files, only relevant when converting .vhx -> .hex;
+    /// required when the input is a .bin file, since raw binary carries no addressing
+    #[clap(short, long, value_parser = legal_u32)]
+    start_address: Option<u32>,
 
     /// Byte value to fill holes in the memory layout with
     #[clap(short, long, value_parser = legal_u8, default_value_t = 0xff)]
     fill: u8,
+
+    /// Largest gap between two runs of data that gets bridged with `fill`;
+    /// wider gaps are kept as separate segments instead of being padded out
+    #[clap(long, value_parser = legal_hex_or_decimal, default_value_t = 1024)]
+    max_gap: usize,
+
+    /// Scan a .hex file for defects and print a statistics report instead of converting
+    #[clap(long)]
+    scan: bool,
+
+    /// When used with `--scan`, rewrite `output` with checksums recomputed, records
+    /// sorted by address, and overlaps resolved to the last write
+    #[clap(long)]
+    fix: bool,
+
+    /// When inspecting (no output path given), show a colored hexdump with an
+    /// ASCII gutter instead of the plain word listing
+    #[clap(long)]
+    dump: bool,
 }
 
 fn legal_u32(arg: &str) -> Result<u32, String> {
@@ -70,7 +91,7 @@ fn legal_file_type(arg: &str) -> Result<PathBuf, String> {
         .ok_or(String::from("No file extension specified"))?;
 
     match extension.to_str().unwrap() {
-        "hex" | "vhx" | "vhx128" => Ok(path),
+        "hex" | "vhx" | "vhx128" | "srec" | "s19" | "s28" | "s37" | "bin" => Ok(path),
         ex => Err(format!("Unsupported file type `{}`", ex)),
     }
 }
@@ -95,25 +116,87 @@ enum Endianness {
 fn main() -> Result<(), String> {
     let args = Cli::parse();
 
+    if args.scan {
+        let report = Payload::scan_hex(&args.input)?;
+
+        if args.fix {
+            let output = args
+                .output
+                .ok_or_else(|| String::from("`--fix` requires an output path to write the repaired file to"))?;
+
+            if output.extension().and_then(|ext| ext.to_str()) != Some("hex") {
+                return Err(format!(
+                    "`--fix` writes Intel HEX; output path `{}` must end in `.hex`",
+                    output.display()
+                ));
+            }
+
+            let output_file = File::create(&output)
+                .map_err(|_| format!("Could not create file `{}`", output.display()))?;
+            let mut output_file = BufWriter::new(output_file);
+
+            let fixed = Payload::fix_hex(
+                &args.input,
+                args.endianness == Endianness::Little,
+                args.fill,
+                args.max_gap,
+            )?;
+            fixed.write_hex(&mut output_file, args.endianness == Endianness::Little);
+
+            output_file
+                .flush()
+                .map_err(|_| format!("Could not write to file `{}`", output.display()))?;
+        }
+
+        print!("{}", report);
+
+        return Ok(());
+    }
+
     let payload = match args.input.extension().unwrap().to_str().unwrap() {
         "hex" => Payload::from_hex(
             &args.input,
             args.endianness == Endianness::Little,
             args.fill,
+            args.max_gap,
         ),
-        "vhx" | "vhx128" => Payload::from_vhx(&args.input, args.start_address as usize, args.chunk_size),
+        "vhx" | "vhx128" => Payload::from_vhx(
+            &args.input,
+            args.start_address.unwrap_or(0) as usize,
+            args.chunk_size,
+            args.fill,
+        ),
+        "srec" | "s19" | "s28" | "s37" => Payload::from_srec(&args.input, args.fill, args.max_gap),
+        "bin" => {
+            let start_address = args.start_address.ok_or_else(|| {
+                String::from(
+                    "`.bin` input requires `--start-address`, since raw binary carries no addressing of its own",
+                )
+            })?;
+
+            Payload::from_bin(&args.input, start_address as usize, args.fill)
+        }
         _ => panic!("Unsupported file type was accepted by argument parser"),
     }?;
 
     if let Some(output) = args.output {
-        let mut output_file = File::create(&output)
+        let output_file = File::create(&output)
             .map_err(|_| format!("Could not create file `{}`", output.display()))?;
+        let mut output_file = BufWriter::new(output_file);
 
         match output.extension().unwrap().to_str().unwrap() {
             "hex" => payload.write_hex(&mut output_file, args.endianness == Endianness::Little),
             "vhx" | "vhx128"  => payload.write_vhx(&mut output_file, args.chunk_size),
+            "srec" | "s19" | "s28" | "s37" => payload.write_srec(&mut output_file),
+            "bin" => payload.write_bin(&mut output_file),
             _ => panic!("Unsupported file type was accepted by argument parser"),
         }
+
+        output_file
+            .flush()
+            .map_err(|_| format!("Could not write to file `{}`", output.display()))?;
+    } else if args.dump {
+        print!("{}", payload.dump(true));
     } else {
         print!("{}", payload);
     }
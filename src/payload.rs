@@ -1,37 +1,314 @@
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 use std::fs;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
 use std::path::Path;
 
 const BITS_IN_WORD: usize = 32;
 const BYTES_IN_WORD: usize = 4;
 const PREFERRED_WORDS_ON_LINE: usize = 4;
+const BYTES_PER_DUMP_LINE: usize = 16;
 
-pub struct Payload {
+/// A contiguous run of real data at a known address. [`Payload`] keeps a
+/// sorted list of these instead of one flat buffer, so a memory image with
+/// data at address `0` and again at `0x0800_0000` doesn't allocate 128 MB
+/// of filler to bridge the gap between them.
+struct Segment {
     start_address: usize,
     bytes: Vec<u8>,
 }
 
+pub struct Payload {
+    segments: Vec<Segment>,
+    fill_value: u8,
+}
+
+/// One decoded Intel HEX record, with both the included and the
+/// freshly computed checksum so callers can decide for themselves
+/// whether a mismatch is fatal.
+struct DecodedLine {
+    record_type: u8,
+    base_address: usize,
+    data: Vec<u8>,
+    computed_checksum: u8,
+    included_checksum: u8,
+}
+
+fn decode_hex_line(line: &str) -> Result<DecodedLine, String> {
+    let body = line
+        .strip_prefix(':')
+        .ok_or_else(|| format!("Record `{}` does not start with `:`", line))?;
+
+    let bytes = hex::decode(body).map_err(|err| err.to_string())?;
+
+    let length = bytes.len();
+
+    if length < 5 {
+        return Err(format!(
+            "Record `{}` is too short to hold a count, address, type and checksum",
+            line
+        ));
+    }
+
+    let count = bytes[0] as usize;
+
+    if length != count + 5 {
+        return Err(format!(
+            "Record `{}` declares {} data bytes but carries {}",
+            line,
+            count,
+            length - 5
+        ));
+    }
+
+    let computed_checksum = hex_checksum(&bytes[0..length - 1]);
+    let included_checksum = bytes.last().copied().unwrap();
+
+    let base_address = (bytes[1] as usize) << 8 | bytes[2] as usize;
+    let record_type = bytes[3];
+    let data = bytes[length - count - 1..length - 1].to_vec();
+
+    Ok(DecodedLine {
+        record_type,
+        base_address,
+        data,
+        computed_checksum,
+        included_checksum,
+    })
+}
+
+/// Fold a sorted, deduplicated address -> byte map into segments, only
+/// bridging a gap between two runs of data with `fill_value` when that gap
+/// is no larger than `max_gap`; wider gaps start a new segment instead of
+/// being filled.
+fn build_segments(memory_map: BTreeMap<usize, u8>, fill_value: u8, max_gap: usize) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+
+    for (address, byte) in memory_map {
+        match segments.last_mut() {
+            Some(segment) => {
+                let next_address = segment.start_address + segment.bytes.len();
+                let gap = address - next_address;
+
+                if gap <= max_gap {
+                    segment.bytes.extend(vec![fill_value; gap]);
+                    segment.bytes.push(byte);
+                } else {
+                    segments.push(Segment {
+                        start_address: address,
+                        bytes: vec![byte],
+                    });
+                }
+            }
+            None => segments.push(Segment {
+                start_address: address,
+                bytes: vec![byte],
+            }),
+        }
+    }
+
+    segments
+}
+
+/// Reverse the byte order of every 4-byte word in `bytes`, padding the
+/// final partial word with `fill_value` so segments of any length can be
+/// word-swapped without panicking.
+fn swap_words(bytes: &[u8], fill_value: u8) -> Vec<u8> {
+    let padding = (BYTES_IN_WORD - bytes.len() % BYTES_IN_WORD) % BYTES_IN_WORD;
+
+    let mut padded = bytes.to_vec();
+    padded.extend(vec![fill_value; padding]);
+
+    padded
+        .chunks_exact(BYTES_IN_WORD)
+        .flat_map(|word| word.iter().rev().copied().collect::<Vec<u8>>())
+        .collect()
+}
+
+/// Statistics gathered by [`Payload::scan_hex`] over a `.hex` file,
+/// without aborting on the first defect encountered.
+pub struct ScanReport {
+    pub total_records: usize,
+    pub data_records: usize,
+    pub checksum_errors: Vec<(usize, u8, u8)>,
+    pub overlaps: Vec<(usize, u8, u8)>,
+    pub out_of_order_records: usize,
+    pub gap_count: usize,
+    pub total_gap_bytes: usize,
+}
+
 impl Payload {
-    pub fn from_hex(file: &Path, little_endian: bool, fill_value: u8) -> Result<Self, String> {
+    /// Wrap a single contiguous run of bytes in the segment model used
+    /// internally; for formats like `.vhx` and `.bin` that carry no gaps
+    /// of their own.
+    fn single_segment(start_address: usize, bytes: Vec<u8>, fill_value: u8) -> Self {
+        Self {
+            segments: vec![Segment {
+                start_address,
+                bytes,
+            }],
+            fill_value,
+        }
+    }
+
+    pub fn from_hex(
+        file: &Path,
+        little_endian: bool,
+        fill_value: u8,
+        max_gap: usize,
+    ) -> Result<Self, String> {
         let file_name = file.display();
 
-        let file_content =
-            fs::read_to_string(file).map_err(|_| format!("Could not read `{}`", file_name))?;
+        let handle = fs::File::open(file).map_err(|_| format!("Could not read `{}`", file_name))?;
+        let reader = BufReader::new(handle);
 
-        let mut memory_map: Vec<(usize, u8)> = Vec::new();
+        let mut memory_map: BTreeMap<usize, u8> = BTreeMap::new();
 
         let mut extended_segment_address = 0;
         let mut extended_linear_address = 0;
 
-        for (line_number, line) in file_content.lines().enumerate() {
-            let bytes = hex::decode(&line[1..])
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|_| format!("Could not read `{}`", file_name))?;
+            let record = decode_hex_line(&line)
+                .map_err(|err| format!("Hex decode error for file `{}`: `{}`", file_name, err))?;
+
+            if record.included_checksum != record.computed_checksum {
+                return Err(format!(
+                    "Checksum mismatch for file `{}` at line {}; `{}` vs `{}`",
+                    file_name, line_number, record.computed_checksum, record.included_checksum
+                ));
+            }
+
+            match record.record_type {
+                0 => {
+                    let base_address = (extended_linear_address << 16)
+                        | (16 * extended_segment_address + record.base_address);
+
+                    for (offset, byte) in record.data.iter().enumerate() {
+                        memory_map.insert(base_address + offset, *byte);
+                    }
+                }
+                1 => break,
+                2 => {
+                    if record.data.len() != 2 {
+                        return Err(format!(
+                            "Incorrect extended segment address length for file `{}` at line {}",
+                            file_name, line_number
+                        ));
+                    }
+                    extended_segment_address =
+                        u16::from_be_bytes([record.data[0], record.data[1]]) as usize;
+                }
+                4 => {
+                    if record.data.len() != 2 {
+                        return Err(format!(
+                            "Incorrect extended linear address length for file `{}` at line {}",
+                            file_name, line_number
+                        ));
+                    }
+                    extended_linear_address =
+                        u16::from_be_bytes([record.data[0], record.data[1]]) as usize;
+                }
+                _ => (),
+            }
+        }
+
+        let mut segments = build_segments(memory_map, fill_value, max_gap);
+
+        if little_endian {
+            for segment in &mut segments {
+                segment.bytes = swap_words(&segment.bytes, fill_value);
+            }
+        }
+
+        Ok(Self {
+            segments,
+            fill_value,
+        })
+    }
+
+    pub fn from_vhx(
+        file: &Path,
+        start_address: usize,
+        chunk_size: usize,
+        fill_value: u8,
+    ) -> Result<Self, String> {
+        let file_name = file.display();
+
+        let handle = fs::File::open(file).map_err(|_| format!("Could not read `{}`", file_name))?;
+        let reader = BufReader::new(handle);
+
+        let chunk_byte_size = (chunk_size / BITS_IN_WORD) * BYTES_IN_WORD;
+
+        let mut bytes = Vec::new();
+        let mut pending = String::new();
+
+        for line in reader.lines() {
+            let line = line.map_err(|_| format!("Could not read `{}`", file_name))?;
+
+            pending.extend(line.chars().filter(|ch| ch.is_ascii_hexdigit()));
+
+            while pending.len() >= chunk_byte_size * 2 {
+                let chunk_hex: String = pending.drain(..chunk_byte_size * 2).collect();
+
+                let chunk = hex::decode(&chunk_hex).map_err(|err| {
+                    format!("Hex decode error for file `{}`: `{}`", file_name, err)
+                })?;
+
+                for word in chunk.chunks_exact(BYTES_IN_WORD).rev() {
+                    bytes.extend_from_slice(word);
+                }
+            }
+        }
+
+        if !pending.is_empty() {
+            return Err(format!(
+                "File `{}` does not contain a complete vhx memory layout",
+                file_name
+            ));
+        }
+
+        Ok(Self::single_segment(start_address, bytes, fill_value))
+    }
+
+    /// Load a Motorola S-record file. Data comes from S1/S2/S3 records
+    /// (16/24/32-bit addressing); S0 headers and S5/S6 count records are
+    /// skipped, and an S7/S8/S9 termination record ends the file.
+    pub fn from_srec(file: &Path, fill_value: u8, max_gap: usize) -> Result<Self, String> {
+        let file_name = file.display();
+
+        let handle = fs::File::open(file).map_err(|_| format!("Could not read `{}`", file_name))?;
+        let reader = BufReader::new(handle);
+
+        let mut memory_map: BTreeMap<usize, u8> = BTreeMap::new();
+
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|_| format!("Could not read `{}`", file_name))?;
+
+            if !line.starts_with('S') || !line.is_char_boundary(2) {
+                return Err(format!(
+                    "Malformed S-record for file `{}` at line {}",
+                    file_name, line_number
+                ));
+            }
+
+            let record_type = line[1..2]
+                .parse::<u8>()
+                .map_err(|_| format!("Malformed S-record for file `{}` at line {}", file_name, line_number))?;
+
+            let bytes = hex::decode(&line[2..])
                 .map_err(|err| format!("Hex decode error for file `{}`: `{}`", file_name, err))?;
 
             let length = bytes.len();
 
-            let computed_checksum = hex_checksum(&bytes[0..length - 1]);
+            if length < 1 {
+                return Err(format!(
+                    "Record `{}` for file `{}` is too short to hold a checksum",
+                    line, file_name
+                ));
+            }
 
+            let computed_checksum = srec_checksum(&bytes[0..length - 1]);
             let included_checksum = bytes.last().copied().unwrap();
 
             if included_checksum != computed_checksum {
@@ -41,204 +318,749 @@ impl Payload {
                 ));
             }
 
-            let count = bytes[0] as usize;
-            let base_address = (bytes[1] as usize) << 8 | bytes[2] as usize;
-            let record_type = bytes[3];
-            let bytes = &bytes[length - count - 1..length - 1];
+            let address_bytes = match record_type {
+                1 | 9 => 2,
+                2 | 8 => 3,
+                3 | 7 => 4,
+                _ => 0,
+            };
 
             match record_type {
-                0 => {
-                    for (offset, byte) in bytes.iter().enumerate() {
-                        memory_map.push((
-                            (extended_linear_address << 16)
-                                | (16 * extended_segment_address
-                                    + base_address
-                                    + (offset as usize)),
-                            *byte,
+                1..=3 => {
+                    if length < address_bytes + 2 {
+                        return Err(format!(
+                            "Record `{}` for file `{}` is too short to hold its address and data",
+                            line, file_name
                         ));
                     }
+
+                    let address = bytes[1..1 + address_bytes]
+                        .iter()
+                        .fold(0usize, |address, &byte| (address << 8) | byte as usize);
+
+                    let data = &bytes[1 + address_bytes..length - 1];
+
+                    for (offset, byte) in data.iter().enumerate() {
+                        memory_map.insert(address + offset, *byte);
+                    }
+                }
+                0 | 5 | 6 | 7 | 8 | 9 => (),
+                _ => {
+                    return Err(format!(
+                        "Unsupported S-record type for file `{}` at line {}",
+                        file_name, line_number
+                    ))
+                }
+            }
+        }
+
+        let segments = build_segments(memory_map, fill_value, max_gap);
+
+        Ok(Self {
+            segments,
+            fill_value,
+        })
+    }
+
+    /// Load a raw binary file verbatim, placing its first byte at
+    /// `start_address`. A flat binary carries no addressing of its own,
+    /// so the caller must supply it.
+    pub fn from_bin(file: &Path, start_address: usize, fill_value: u8) -> Result<Self, String> {
+        let file_name = file.display();
+
+        let bytes = fs::read(file).map_err(|_| format!("Could not read `{}`", file_name))?;
+
+        Ok(Self::single_segment(start_address, bytes, fill_value))
+    }
+
+    /// Walk every record of a `.hex` file, collecting defects instead of
+    /// aborting on the first one, and return a statistics report.
+    pub fn scan_hex(file: &Path) -> Result<ScanReport, String> {
+        let file_name = file.display();
+
+        let handle = fs::File::open(file).map_err(|_| format!("Could not read `{}`", file_name))?;
+        let reader = BufReader::new(handle);
+
+        let mut memory_map: BTreeMap<usize, u8> = BTreeMap::new();
+
+        let mut extended_segment_address = 0;
+        let mut extended_linear_address = 0;
+
+        let mut total_records = 0;
+        let mut data_records = 0;
+        let mut checksum_errors = Vec::new();
+        let mut overlaps = Vec::new();
+        let mut out_of_order_records = 0;
+        let mut last_data_address: Option<usize> = None;
+
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|_| format!("Could not read `{}`", file_name))?;
+            let record = decode_hex_line(&line)
+                .map_err(|err| format!("Hex decode error for file `{}`: `{}`", file_name, err))?;
+
+            total_records += 1;
+
+            if record.included_checksum != record.computed_checksum {
+                checksum_errors.push((
+                    line_number,
+                    record.computed_checksum,
+                    record.included_checksum,
+                ));
+            }
+
+            match record.record_type {
+                0 => {
+                    data_records += 1;
+
+                    let base_address = (extended_linear_address << 16)
+                        | (16 * extended_segment_address + record.base_address);
+
+                    if let Some(last_address) = last_data_address {
+                        if base_address < last_address {
+                            out_of_order_records += 1;
+                        }
+                    }
+                    last_data_address = Some(base_address);
+
+                    for (offset, byte) in record.data.iter().enumerate() {
+                        let address = base_address + offset;
+
+                        if let Some(&old) = memory_map.get(&address) {
+                            overlaps.push((address, old, *byte));
+                        }
+
+                        memory_map.insert(address, *byte);
+                    }
                 }
                 1 => break,
                 2 => {
-                    if count != 2 {
+                    if record.data.len() != 2 {
                         return Err(format!(
                             "Incorrect extended segment address length for file `{}` at line {}",
                             file_name, line_number
                         ));
                     }
-                    extended_segment_address = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+                    extended_segment_address =
+                        u16::from_be_bytes([record.data[0], record.data[1]]) as usize;
                 }
                 4 => {
-                    if count != 2 {
+                    if record.data.len() != 2 {
                         return Err(format!(
                             "Incorrect extended linear address length for file `{}` at line {}",
                             file_name, line_number
                         ));
                     }
-                    extended_linear_address = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+                    extended_linear_address =
+                        u16::from_be_bytes([record.data[0], record.data[1]]) as usize;
                 }
                 _ => (),
             }
         }
 
-        memory_map.sort();
+        let mut gap_count = 0;
+        let mut total_gap_bytes = 0;
+        let mut previous_address: Option<usize> = None;
 
-        let start_address = match memory_map.first() {
-            None => 0,
-            Some((address, _byte)) => *address,
-        };
+        for &address in memory_map.keys() {
+            if let Some(previous) = previous_address {
+                let gap = address - previous - 1;
 
-        let size = match memory_map.last() {
-            None => 0,
-            Some((address, _byte)) => *address - start_address,
-        };
+                if gap > 0 {
+                    gap_count += 1;
+                    total_gap_bytes += gap;
+                }
+            }
 
-        let raw_bytes = memory_map
-            .iter()
-            .fold(
-                (start_address, Vec::with_capacity(size)),
-                |(last_address, mut acc), &(address, byte)| {
-                    let mut fill = match address - last_address {
-                        0 | 1 => Vec::new(),
-                        gap_size => vec![fill_value; gap_size - 1],
-                    };
+            previous_address = Some(address);
+        }
 
-                    acc.append(&mut fill);
-                    acc.push(byte);
+        Ok(ScanReport {
+            total_records,
+            data_records,
+            checksum_errors,
+            overlaps,
+            out_of_order_records,
+            gap_count,
+            total_gap_bytes,
+        })
+    }
 
-                    (address, acc)
-                },
-            )
-            .1;
+    /// Rebuild a `.hex` file's memory map the same way [`Payload::from_hex`]
+    /// does, but tolerate the defects [`Payload::scan_hex`] reports instead
+    /// of failing: checksums are ignored on read (and recomputed on write
+    /// through [`Payload::write_hex`]), and overlapping writes resolve to
+    /// the last record that touched the address.
+    pub fn fix_hex(
+        file: &Path,
+        little_endian: bool,
+        fill_value: u8,
+        max_gap: usize,
+    ) -> Result<Self, String> {
+        let file_name = file.display();
 
-        let bytes = if little_endian {
-            assert!(raw_bytes.len() % BYTES_IN_WORD == 0);
+        let handle = fs::File::open(file).map_err(|_| format!("Could not read `{}`", file_name))?;
+        let reader = BufReader::new(handle);
 
-            raw_bytes
-                .chunks_exact(BYTES_IN_WORD)
-                .map(|word| word.iter().rev().copied().collect::<Vec<u8>>())
-                .flatten()
-                .collect()
-        } else {
-            raw_bytes
-        };
+        let mut memory_map: BTreeMap<usize, u8> = BTreeMap::new();
+
+        let mut extended_segment_address = 0;
+        let mut extended_linear_address = 0;
+
+        for line in reader.lines() {
+            let line = line.map_err(|_| format!("Could not read `{}`", file_name))?;
+            let record = decode_hex_line(&line)
+                .map_err(|err| format!("Hex decode error for file `{}`: `{}`", file_name, err))?;
+
+            match record.record_type {
+                0 => {
+                    let base_address = (extended_linear_address << 16)
+                        | (16 * extended_segment_address + record.base_address);
+
+                    for (offset, byte) in record.data.iter().enumerate() {
+                        memory_map.insert(base_address + offset, *byte);
+                    }
+                }
+                1 => break,
+                2 if record.data.len() == 2 => {
+                    extended_segment_address =
+                        u16::from_be_bytes([record.data[0], record.data[1]]) as usize;
+                }
+                4 if record.data.len() == 2 => {
+                    extended_linear_address =
+                        u16::from_be_bytes([record.data[0], record.data[1]]) as usize;
+                }
+                _ => (),
+            }
+        }
+
+        let mut segments = build_segments(memory_map, fill_value, max_gap);
+
+        if little_endian {
+            for segment in &mut segments {
+                segment.bytes = swap_words(&segment.bytes, fill_value);
+            }
+        }
 
         Ok(Self {
-            start_address,
-            bytes,
+            segments,
+            fill_value,
         })
     }
 
-    pub fn from_vhx(file: &Path, start_address: usize, chunk_size: usize) -> Result<Self, String> {
-        let file_name = file.display();
+    pub fn write_hex(&self, file: &mut impl Write, little_endian: bool) {
+        let step_size = PREFERRED_WORDS_ON_LINE * BYTES_IN_WORD;
 
-        let file_content = hex::decode(
-            fs::read_to_string(file)
-                .map_err(|_| format!("Could not read `{}`", file_name))?
-                .chars()
-                .filter(|ch| ch.is_ascii_hexdigit())
-                .collect::<String>(),
-        )
-        .map_err(|err| format!("Hex decode error for file `{}`: `{}`", file_name, err))?;
+        for segment in &self.segments {
+            let start_address = (segment.start_address as u32).to_be_bytes();
+            let extended_linear = [0x02, 0x00, 0x00, 0x04, start_address[0], start_address[1]];
+            let extended_checksum = [hex_checksum(&extended_linear)];
 
-        let word_chunk_size = chunk_size / BITS_IN_WORD;
+            writeln!(
+                file,
+                ":{}{}",
+                hex::encode_upper(&extended_linear),
+                hex::encode_upper(&extended_checksum)
+            )
+            .expect("Unable to write to file");
 
-        if file_content.len() % (word_chunk_size * BYTES_IN_WORD) != 0 {
-            return Err(format!(
-                "File `{}` does not contain a complete vhx memory layout",
-                file_name
-            ));
-        }
+            for (offset, word_group) in segment.bytes.chunks(step_size).enumerate() {
+                let word_padding = (BYTES_IN_WORD - word_group.len() % BYTES_IN_WORD) % BYTES_IN_WORD;
 
-        let mut bytes = Vec::with_capacity(file_content.len());
+                let mut word_group = word_group.to_vec();
+                word_group.extend(vec![self.fill_value; word_padding]);
 
-        for line in file_content.chunks_exact(word_chunk_size * BYTES_IN_WORD) {
-            for word in line.chunks_exact(BYTES_IN_WORD).rev() {
-                bytes.extend_from_slice(word);
+                let sub_address = ((segment.start_address + offset * step_size) as u32).to_be_bytes();
+                let header = [word_group.len() as u8, sub_address[2], sub_address[3], 0x00];
+
+                let mut line = Vec::with_capacity(header.len() + word_group.len() + 1);
+                line.extend(&header);
+
+                for byte_group in word_group.chunks_exact(BYTES_IN_WORD) {
+                    if little_endian {
+                        line.extend(byte_group.iter().rev());
+                    } else {
+                        line.extend(byte_group);
+                    }
+                }
+
+                line.push(hex_checksum(&line));
+
+                writeln!(file, ":{}", hex::encode_upper(&line)).unwrap();
             }
         }
 
-        Ok(Self {
-            start_address,
-            bytes,
-        })
+        writeln!(file, ":00000001FF").unwrap();
     }
 
-    pub fn write_hex(&self, file: &mut fs::File, little_endian: bool) {
-        let start_address = (self.start_address as u32).to_be_bytes();
-        let extended_segment = [0x02, 0x00, 0x00, 0x04, start_address[0], start_address[1]];
-        let extended_checksum = [hex_checksum(&extended_segment)];
+    /// Emit each segment's bytes as its own run of words, padding only up
+    /// to that segment's own chunk boundary so a gap between segments
+    /// doesn't get padded into the output.
+    pub fn write_vhx(&self, file: &mut impl Write, chunk_size: usize) {
+        let word_chunk_size = chunk_size / BITS_IN_WORD;
+        let chunk_byte_size = word_chunk_size * BYTES_IN_WORD;
 
-        writeln!(
-            file,
-            ":{}{}",
-            hex::encode_upper(&extended_segment),
-            hex::encode_upper(&extended_checksum)
-        )
-        .expect("Unable to write to file");
+        for segment in &self.segments {
+            let padding = (chunk_byte_size - segment.bytes.len() % chunk_byte_size) % chunk_byte_size;
+
+            let mut bytes = segment.bytes.clone();
+            bytes.extend(vec![self.fill_value; padding]);
+
+            let words: Vec<String> = bytes
+                .chunks_exact(BYTES_IN_WORD)
+                .map(|word| hex::encode(word))
+                .collect();
+
+            for chunk in words.chunks_exact(word_chunk_size) {
+                for word in chunk.iter().rev() {
+                    write!(file, "{}", word).expect("Unable to write to file");
+                }
+                writeln!(file).unwrap();
+            }
+        }
+    }
+
+    /// Write a Motorola S-record file, picking S1/S2/S3 data records
+    /// (and the matching S9/S8/S7 terminator) automatically from the
+    /// highest address in the payload. Each segment gets its own run of
+    /// data records, addressed from that segment's own start address.
+    pub fn write_srec(&self, file: &mut impl Write) {
+        let header_data = b"HDR";
+        let mut header = Vec::with_capacity(2 + header_data.len() + 1);
+        header.push((2 + header_data.len() + 1) as u8);
+        header.extend_from_slice(&[0x00, 0x00]);
+        header.extend_from_slice(header_data);
+        header.push(srec_checksum(&header));
+
+        writeln!(file, "S0{}", hex::encode_upper(&header)).unwrap();
+
+        let highest_address = self
+            .segments
+            .iter()
+            .map(|segment| segment.start_address + segment.bytes.len().saturating_sub(1))
+            .max()
+            .unwrap_or(0);
+
+        let (data_type, term_type, address_bytes) = if highest_address <= 0xFFFF {
+            (1, 9, 2)
+        } else if highest_address <= 0xFF_FFFF {
+            (2, 8, 3)
+        } else {
+            (3, 7, 4)
+        };
 
         let step_size = PREFERRED_WORDS_ON_LINE * BYTES_IN_WORD;
 
-        for (offset, word_group) in self.bytes.chunks(step_size).enumerate() {
-            let sub_address = ((self.start_address + offset * step_size) as u32).to_be_bytes();
-            let header = [word_group.len() as u8, sub_address[2], sub_address[3], 0x00];
+        for segment in &self.segments {
+            for (offset, word_group) in segment.bytes.chunks(step_size).enumerate() {
+                let address = (segment.start_address + offset * step_size) as u32;
+                let address_field = address.to_be_bytes();
 
-            let mut line = Vec::with_capacity(header.len() + word_group.len() + 1);
-            line.extend(&header);
+                let mut record = Vec::with_capacity(address_bytes + word_group.len() + 1);
+                record.push((address_bytes + word_group.len() + 1) as u8);
+                record.extend_from_slice(&address_field[4 - address_bytes..]);
+                record.extend_from_slice(word_group);
 
-            for byte_group in word_group.chunks_exact(BYTES_IN_WORD) {
-                if little_endian {
-                    line.extend(byte_group.iter().rev());
-                } else {
-                    line.extend(byte_group);
-                }
+                record.push(srec_checksum(&record));
+
+                writeln!(file, "S{}{}", data_type, hex::encode_upper(&record)).unwrap();
             }
+        }
+
+        let term_start_address = self.segments.first().map_or(0, |segment| segment.start_address);
+        let term_address = (term_start_address as u32).to_be_bytes();
+        let mut terminator = Vec::with_capacity(address_bytes + 1);
+        terminator.push((address_bytes + 1) as u8);
+        terminator.extend_from_slice(&term_address[4 - address_bytes..]);
+        terminator.push(srec_checksum(&terminator));
+
+        writeln!(file, "S{}{}", term_type, hex::encode_upper(&terminator)).unwrap();
+    }
+
+    /// Dump the memory image verbatim, with no record framing or addressing.
+    /// A flat binary has no way to represent an address, so any gap between
+    /// segments is bridged with `fill_value` to keep every later byte at its
+    /// correct offset from the first segment's start address.
+    pub fn write_bin(&self, file: &mut impl Write) {
+        let mut next_address = None;
 
-            line.push(hex_checksum(&line));
+        for segment in &self.segments {
+            if let Some(next_address) = next_address {
+                let gap = segment.start_address - next_address;
+                file.write_all(&vec![self.fill_value; gap])
+                    .expect("Unable to write to file");
+            }
 
-            writeln!(file, ":{}", hex::encode_upper(&line),).unwrap();
+            file.write_all(&segment.bytes).expect("Unable to write to file");
+            next_address = Some(segment.start_address + segment.bytes.len());
         }
+    }
 
-        writeln!(file, ":00000001FF").unwrap();
+    /// Pair this payload with a color choice for use with `{}` formatting;
+    /// see [`Payload::write_dump`] for the layout.
+    pub fn dump(&self, color: bool) -> Dump<'_> {
+        Dump {
+            payload: self,
+            color,
+        }
     }
 
-    pub fn write_vhx(&self, file: &mut fs::File, chunk_size: usize) {
-        let words: Vec<String> = self
-            .bytes
-            .chunks_exact(BYTES_IN_WORD)
-            .map(|word| hex::encode(word))
-            .collect();
+    /// Render a colored hexdump with a 32-bit address column, bytes grouped
+    /// in two columns of eight, and an ASCII gutter, modeled on the classic
+    /// HexView layout. When `color` is set, bytes equal to `fill_value`
+    /// (inserted to pad gaps) are dimmed so real payload data stands out.
+    /// Segment boundaries are marked with the size of the gap between them.
+    pub fn write_dump(&self, f: &mut Formatter, color: bool) -> std::fmt::Result {
+        for (segment_index, segment) in self.segments.iter().enumerate() {
+            if segment_index > 0 {
+                let previous = &self.segments[segment_index - 1];
+                let gap = segment.start_address - (previous.start_address + previous.bytes.len());
 
-        for chunk in words.chunks_exact(chunk_size / BITS_IN_WORD) {
-            for word in chunk.iter().rev() {
-                write!(file, "{}", word).expect("Unable to write to file");
+                writeln!(f, "-- gap of {} bytes --", gap)?;
+            }
+
+            for (i, line) in segment.bytes.chunks(BYTES_PER_DUMP_LINE).enumerate() {
+                let offset = i * BYTES_PER_DUMP_LINE;
+                let address = ((segment.start_address + offset) as u32).to_be_bytes();
+
+                write!(f, "{}: ", hex::encode(address))?;
+
+                for (group_index, group) in line.chunks(8).enumerate() {
+                    if group_index > 0 {
+                        write!(f, "  ")?;
+                    }
+
+                    for byte in group {
+                        if color && *byte == self.fill_value {
+                            write!(f, "\x1b[2m{:02X}\x1b[0m ", byte)?;
+                        } else {
+                            write!(f, "{:02X} ", byte)?;
+                        }
+                    }
+                }
+
+                write!(f, " |")?;
+
+                for byte in line {
+                    let ch = if byte.is_ascii_graphic() || *byte == b' ' {
+                        *byte as char
+                    } else {
+                        '.'
+                    };
+
+                    write!(f, "{}", ch)?;
+                }
+
+                writeln!(f, "|")?;
             }
-            writeln!(file, "").unwrap();
         }
+
+        Ok(())
+    }
+}
+
+/// A [`Payload`] paired with a color choice, returned by [`Payload::dump`]
+/// for use with `{}` formatting.
+pub struct Dump<'a> {
+    payload: &'a Payload,
+    color: bool,
+}
+
+impl Display for Dump<'_> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        self.payload.write_dump(f, self.color)
     }
 }
 
+fn byte_sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |acc, &x| acc.wrapping_add(x))
+}
+
 fn hex_checksum(bytes: &[u8]) -> u8 {
-    (bytes.iter().fold(0u8, |acc, &x| acc.wrapping_add(x)) ^ 0xff).wrapping_add(1u8)
+    (byte_sum(bytes) ^ 0xff).wrapping_add(1u8)
+}
+
+fn srec_checksum(bytes: &[u8]) -> u8 {
+    byte_sum(bytes) ^ 0xff
 }
 
 impl Display for Payload {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        let words: Vec<String> = self
-            .bytes
-            .chunks_exact(BYTES_IN_WORD)
-            .map(|word| hex::encode(word))
-            .collect();
+        for (segment_index, segment) in self.segments.iter().enumerate() {
+            if segment_index > 0 {
+                let previous = &self.segments[segment_index - 1];
+                let gap = segment.start_address - (previous.start_address + previous.bytes.len());
+
+                writeln!(f, "-- gap of {} bytes --", gap)?;
+            }
+
+            let words: Vec<String> = segment
+                .bytes
+                .chunks_exact(BYTES_IN_WORD)
+                .map(|word| hex::encode(word))
+                .collect();
+
+            for (i, word_group) in words.chunks(PREFERRED_WORDS_ON_LINE).enumerate() {
+                let offset = i * PREFERRED_WORDS_ON_LINE * BYTES_IN_WORD;
+                let address = ((segment.start_address + offset) as u32).to_be_bytes();
+
+                let values = word_group.to_owned().join(" ");
+
+                writeln!(f, "{}: {}", hex::encode(&address), values)?;
+            }
+        }
+
+        Ok(())
+    }
+}
 
-        for (i, word_group) in words.chunks(PREFERRED_WORDS_ON_LINE).enumerate() {
-            let offset = i * PREFERRED_WORDS_ON_LINE * BYTES_IN_WORD;
-            let address = ((self.start_address + offset) as u32).to_be_bytes();
 
-            let values = word_group.to_owned().join(" ");
+impl Display for ScanReport {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "Total records:         {}", self.total_records)?;
+        writeln!(f, "Data records:          {}", self.data_records)?;
+        writeln!(f, "Checksum errors:       {}", self.checksum_errors.len())?;
+        writeln!(f, "Overlapping writes:    {}", self.overlaps.len())?;
+        writeln!(f, "Out-of-order records:  {}", self.out_of_order_records)?;
+        writeln!(f, "Gaps:                  {}", self.gap_count)?;
+        writeln!(f, "Gap bytes:             {}", self.total_gap_bytes)?;
 
-            writeln!(f, "{}: {}", hex::encode(&address), values)?;
+        if !self.checksum_errors.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Checksum errors (line, expected, found):")?;
+            for (line, expected, found) in &self.checksum_errors {
+                writeln!(
+                    f,
+                    "  line {}: expected {:#04x}, found {:#04x}",
+                    line, expected, found
+                )?;
+            }
+        }
+
+        if !self.overlaps.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Overlapping writes (address, old, new):")?;
+            for (address, old, new) in &self.overlaps {
+                writeln!(
+                    f,
+                    "  {:#010x}: {:#04x} -> {:#04x}",
+                    address, old, new
+                )?;
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_hex_line_reads_a_valid_record() {
+        let record = decode_hex_line(":04000000DEADBEEFC4").unwrap();
+
+        assert_eq!(record.record_type, 0);
+        assert_eq!(record.base_address, 0x0000);
+        assert_eq!(record.data, vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(record.included_checksum, record.computed_checksum);
+    }
+
+    #[test]
+    fn decode_hex_line_rejects_a_blank_line_instead_of_panicking() {
+        assert!(decode_hex_line("").is_err());
+    }
+
+    #[test]
+    fn decode_hex_line_rejects_a_count_that_overstates_the_data_present() {
+        // Declares 4 data bytes but only carries 1.
+        assert!(decode_hex_line(":04000000DE00").is_err());
+    }
+
+    #[test]
+    fn build_segments_bridges_a_gap_within_max_gap() {
+        let mut memory_map: BTreeMap<usize, u8> = BTreeMap::new();
+        memory_map.insert(0x0000, 0x11);
+        memory_map.insert(0x0004, 0x22);
+
+        let segments = build_segments(memory_map, 0xff, 4);
+
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].start_address, 0x0000);
+        assert_eq!(segments[0].bytes, vec![0x11, 0xff, 0xff, 0xff, 0x22]);
+    }
+
+    #[test]
+    fn build_segments_splits_a_gap_beyond_max_gap() {
+        let mut memory_map: BTreeMap<usize, u8> = BTreeMap::new();
+        memory_map.insert(0x0000, 0x11);
+        memory_map.insert(0x0006, 0x22);
+
+        let segments = build_segments(memory_map, 0xff, 4);
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].start_address, 0x0000);
+        assert_eq!(segments[0].bytes, vec![0x11]);
+        assert_eq!(segments[1].start_address, 0x0006);
+        assert_eq!(segments[1].bytes, vec![0x22]);
+    }
+
+    #[test]
+    fn swap_words_pads_a_trailing_partial_word() {
+        let swapped = swap_words(&[0x11, 0x22, 0x33], 0xff);
+
+        assert_eq!(swapped, vec![0xff, 0x33, 0x22, 0x11]);
+    }
+
+    #[test]
+    fn write_hex_pads_a_trailing_partial_word_instead_of_dropping_it() {
+        let payload = Payload::single_segment(0x0000, vec![0x11, 0x22, 0x33], 0xff);
+
+        let mut out = Vec::new();
+        payload.write_hex(&mut out, false);
+
+        let text = String::from_utf8(out).unwrap();
+        let data_record = text.lines().nth(1).unwrap();
+
+        // Count byte 04 means all three real bytes plus the fill byte made it in.
+        assert_eq!(&data_record[1..3], "04");
+        assert_eq!(&data_record[9..17], "112233FF");
+    }
+
+    #[test]
+    fn write_bin_fills_the_gap_between_segments() {
+        let payload = Payload {
+            segments: vec![
+                Segment {
+                    start_address: 0x0000,
+                    bytes: vec![0x11, 0x22],
+                },
+                Segment {
+                    start_address: 0x0005,
+                    bytes: vec![0x33],
+                },
+            ],
+            fill_value: 0xff,
+        };
+
+        let mut out = Vec::new();
+        payload.write_bin(&mut out);
+
+        assert_eq!(out, vec![0x11, 0x22, 0xff, 0xff, 0xff, 0x33]);
+    }
+
+    /// Exercises the streaming read/write path end to end: [`Payload::write_hex`]
+    /// buffers through a `Vec<u8>`, the encoded bytes are persisted to disk, and
+    /// [`Payload::from_hex`] reads them back line by line through a `BufReader`.
+    #[test]
+    fn hex_round_trip_preserves_bytes() {
+        let payload = Payload {
+            segments: vec![Segment {
+                start_address: 0x0000,
+                bytes: (0u8..16).collect(),
+            }],
+            fill_value: 0xff,
+        };
+
+        let mut encoded = Vec::new();
+        payload.write_hex(&mut encoded, false);
+
+        let path = std::env::temp_dir().join("stupedama_test_hex_round_trip.hex");
+        fs::write(&path, &encoded).unwrap();
+        let decoded = Payload::from_hex(&path, false, 0xff, 1024);
+        fs::remove_file(&path).ok();
+
+        let decoded = decoded.unwrap();
+
+        assert_eq!(decoded.segments.len(), 1);
+        assert_eq!(decoded.segments[0].bytes, (0u8..16).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn from_bin_honors_the_fill_value_instead_of_a_hardcoded_default() {
+        let path = std::env::temp_dir().join("stupedama_test_from_bin_fill_value.bin");
+        fs::write(&path, vec![0x11, 0x22]).unwrap();
+
+        let payload = Payload::from_bin(&path, 0x0000, 0xaa).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(payload.fill_value, 0xaa);
+        assert_eq!(payload.segments[0].bytes, vec![0x11, 0x22]);
+    }
+
+    #[test]
+    fn from_vhx_honors_the_fill_value_instead_of_a_hardcoded_default() {
+        let path = std::env::temp_dir().join("stupedama_test_from_vhx_fill_value.vhx");
+        fs::write(&path, "1122334455667788 99AABBCCDDEEFF00\n").unwrap();
+
+        let payload = Payload::from_vhx(&path, 0x0000, 128, 0xaa).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(payload.fill_value, 0xaa);
+        assert_eq!(
+            payload.segments[0].bytes,
+            vec![
+                0xdd, 0xee, 0xff, 0x00, 0x99, 0xaa, 0xbb, 0xcc, 0x55, 0x66, 0x77, 0x88, 0x11,
+                0x22, 0x33, 0x44,
+            ]
+        );
+    }
+
+    #[test]
+    fn from_srec_reads_a_valid_data_record() {
+        let path = std::env::temp_dir().join("stupedama_test_from_srec_valid.srec");
+        fs::write(
+            &path,
+            "S1130000214601360121470136007EFE09D219013D\nS5030001FB\nS9030000FC\n",
+        )
+        .unwrap();
+
+        let payload = Payload::from_srec(&path, 0xff, 1024);
+        fs::remove_file(&path).ok();
+
+        let payload = payload.unwrap();
+
+        assert_eq!(payload.segments.len(), 1);
+        assert_eq!(payload.segments[0].start_address, 0x0000);
+        assert_eq!(
+            payload.segments[0].bytes,
+            vec![
+                0x21, 0x46, 0x01, 0x36, 0x01, 0x21, 0x47, 0x01, 0x36, 0x00, 0x7e, 0xfe, 0x09,
+                0xd2, 0x19, 0x01,
+            ]
+        );
+    }
+
+    #[test]
+    fn from_srec_rejects_a_line_too_short_to_hold_a_type_digit_instead_of_panicking() {
+        let path = std::env::temp_dir().join("stupedama_test_from_srec_short_type.srec");
+        fs::write(&path, "S\n").unwrap();
+
+        let result = Payload::from_srec(&path, 0xff, 1024);
+        fs::remove_file(&path).ok();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn from_srec_rejects_a_line_with_a_multi_byte_character_after_the_type_digit_instead_of_panicking() {
+        let path = std::env::temp_dir().join("stupedama_test_from_srec_multibyte.srec");
+        fs::write(&path, "Sé\n").unwrap();
+
+        let result = Payload::from_srec(&path, 0xff, 1024);
+        fs::remove_file(&path).ok();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn from_srec_rejects_a_record_too_short_to_hold_a_checksum_instead_of_panicking() {
+        let path = std::env::temp_dir().join("stupedama_test_from_srec_no_checksum.srec");
+        fs::write(&path, "S1\n").unwrap();
+
+        let result = Payload::from_srec(&path, 0xff, 1024);
+        fs::remove_file(&path).ok();
+
+        assert!(result.is_err());
+    }
+}